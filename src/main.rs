@@ -1,5 +1,7 @@
 use std::cmp;
 
+use rand::Rng;
+use rand::SeedableRng;
 use tcod::colors::*;
 use tcod::console::*;
 use tcod::chars;
@@ -9,14 +11,53 @@ use tcod::input::KeyCode::*;
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
 const LIMIT_FPS: i32 = 20;
-const MAP_WIDTH: i32 = 80;
-const MAP_HEIGHT: i32 = 45;
+const DISPLAY_WIDTH: i32 = 80;
+const DISPLAY_HEIGHT: i32 = 45;
+const MAP_WIDTH: i32 = 160;
+const MAP_HEIGHT: i32 = 90;
+const ROOM_MIN_SIZE: i32 = 6;
+const ROOM_MAX_SIZE: i32 = 10;
+const MAX_ROOMS: i32 = 30;
+const MAP_SEED: u64 = 1337;
 const COLOR_DARK_WALL: Color = Color {r: 0, g: 0, b: 100};
 const COLOR_DARK_GROUND: Color = Color {r: 50, g: 50, b: 150};
+const COLOR_LIGHT_WALL: Color = Color {r: 130, g: 110, b: 50};
+const COLOR_LIGHT_GROUND: Color = Color {r: 200, g: 180, b: 50};
+const PANEL_HEIGHT: i32 = SCREEN_HEIGHT - DISPLAY_HEIGHT;
+const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
+const MSG_X: i32 = 1;
+const MSG_WIDTH: i32 = SCREEN_WIDTH - 2;
+const MSG_HEIGHT: i32 = PANEL_HEIGHT - 1;
 
 struct Tcod {
     root: Root,
     con: Offscreen,
+    panel: Offscreen,
+    camera: Camera,
+}
+
+/// A viewport window onto the map, centered on the player. The map can be
+/// far larger than the screen; the camera tracks which slice is visible.
+struct Camera {
+    left_x: i32,
+    right_x: i32,
+    top_y: i32,
+    bottom_y: i32,
+}
+
+impl Camera {
+    pub fn new(px: i32, py: i32) -> Self {
+        let mut camera = Self {left_x: 0, right_x: 0, top_y: 0, bottom_y: 0};
+        camera.on_player_move(px, py);
+        camera
+    }
+
+    pub fn on_player_move(&mut self, px: i32, py: i32) {
+        self.left_x = cmp::max(0, cmp::min(px - DISPLAY_WIDTH / 2, MAP_WIDTH - DISPLAY_WIDTH));
+        self.top_y = cmp::max(0, cmp::min(py - DISPLAY_HEIGHT / 2, MAP_HEIGHT - DISPLAY_HEIGHT));
+        self.right_x = self.left_x + DISPLAY_WIDTH;
+        self.bottom_y = self.top_y + DISPLAY_HEIGHT;
+    }
 }
 
 struct Object {
@@ -24,11 +65,13 @@ struct Object {
     y: i32,
     char: char,
     color: Color,
+    viewshed: Option<Viewshed>,
+    ai: Option<Ai>,
 }
 
 impl Object {
     pub fn new(x: i32, y: i32, char: char, color: Color) -> Self {
-        Self {x, y, char, color}
+        Self {x, y, char, color, viewshed: None, ai: None}
     }
 
     pub fn move_by(&mut self, dx: i32, dy: i32, map: &Map) {
@@ -37,15 +80,34 @@ impl Object {
         if !map[x as usize][y as usize].blocked {
             self.x = x;
             self.y = y;
+            if let Some(viewshed) = &mut self.viewshed {
+                viewshed.dirty = true;
+            }
         }
     }
+}
 
-    pub fn draw(&self, con: &mut dyn Console) {
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
+/// What an object can currently see, and how far it can see it.
+struct Viewshed {
+    visible_tiles: Vec<(i32, i32)>,
+    range: i32,
+    dirty: bool,
+}
+
+impl Viewshed {
+    pub fn new(range: i32) -> Self {
+        Self {visible_tiles: Vec::new(), range, dirty: true}
     }
 }
 
+/// Marks an object as monster-controlled. The only behavior so far is
+/// `Basic`: step straight toward the player whenever it stands inside the
+/// player's viewshed (monsters have no viewshed of their own).
+#[derive(Clone, Copy, Debug)]
+enum Ai {
+    Basic,
+}
+
 #[derive(Clone, Copy, Debug)]
 struct Tile {
     blocked: bool,
@@ -71,6 +133,14 @@ impl Rect {
     pub fn new(x1: i32, y1: i32, w: i32, h: i32) -> Self {
         Self { x1, x2: x1 + w, y1, y2: y1 + h}
     }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x1 <= other.x2 && self.x2 >= other.x1 && self.y1 <= other.y2 && self.y2 >= other.y1
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        ((self.x1 + self.x2) / 2, (self.y1 + self.y2) / 2)
+    }
 }
 
 fn add_room(room: Rect, map: &mut Map) {
@@ -96,65 +166,333 @@ type Map = Vec<Vec<Tile>>;
 struct Game {
     map: Map,
     objects: Vec<Object>,
+    explored: Vec<Vec<bool>>,
+    state: RunState,
+    log: Vec<(String, Color)>,
+}
+
+impl Game {
+    /// Appends a message to the log; the panel only ever shows the most
+    /// recent lines that fit, so older entries simply scroll off.
+    pub fn log(&mut self, text: impl Into<String>, color: Color) {
+        self.log.push((text.into(), color));
+    }
+}
+
+/// Drives the turn structure: the world only advances in response to a
+/// player action, rather than every frame.
+#[derive(Clone, Copy, Debug)]
+enum RunState {
+    PreRun,
+    AwaitingInput,
+    PlayerTurn,
+    MonsterTurn,
+}
+
+/// Octant transforms (xx, xy, yx, yy) used to turn the row/col coordinates
+/// `cast_light` works in back into world coordinates, one entry per octant.
+const FOV_OCTANTS: [[i32; 4]; 8] = [
+    [1, 0, 0, -1],
+    [0, 1, -1, 0],
+    [0, -1, -1, 0],
+    [-1, 0, 0, -1],
+    [-1, 0, 0, 1],
+    [0, -1, 1, 0],
+    [0, 1, 1, 0],
+    [1, 0, 0, 1],
+];
+
+fn is_blocked(map: &Map, x: i32, y: i32) -> bool {
+    if x < 0 || y < 0 || x >= MAP_WIDTH || y >= MAP_HEIGHT {
+        true
+    } else {
+        map[x as usize][y as usize].block_sight
+    }
 }
 
-fn make_map() -> Map {
+/// Recursive shadowcasting over a single octant: scans rows outward from the
+/// origin, narrowing to the wedge between `start` and `end` slopes, and
+/// recurses into the sub-wedge before a wall whenever sight is blocked.
+#[allow(clippy::too_many_arguments)]
+fn cast_light(
+    map: &Map,
+    cx: i32,
+    cy: i32,
+    row: i32,
+    mut start: f32,
+    end: f32,
+    range: i32,
+    xx: i32,
+    xy: i32,
+    yx: i32,
+    yy: i32,
+    visible: &mut Vec<(i32, i32)>,
+) {
+    if start < end {
+        return;
+    }
+    let range_squared = range * range;
+    let mut blocked = false;
+    let mut next_start = start;
+    for j in row..=range {
+        let dy = -j;
+        let mut dx = -j - 1;
+        while dx <= 0 {
+            dx += 1;
+            let wx = cx + dx * xx + dy * xy;
+            let wy = cy + dx * yx + dy * yy;
+            let l_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let r_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+            if start < r_slope {
+                continue;
+            } else if end > l_slope {
+                break;
+            }
+            if dx * dx + dy * dy < range_squared
+                && wx >= 0 && wy >= 0 && wx < MAP_WIDTH && wy < MAP_HEIGHT
+            {
+                visible.push((wx, wy));
+            }
+            if blocked {
+                if is_blocked(map, wx, wy) {
+                    next_start = r_slope;
+                    continue;
+                }
+                blocked = false;
+                start = next_start;
+            } else if is_blocked(map, wx, wy) && j < range {
+                blocked = true;
+                cast_light(map, cx, cy, j + 1, start, l_slope, range, xx, xy, yx, yy, visible);
+                next_start = r_slope;
+            }
+        }
+        if blocked {
+            break;
+        }
+    }
+}
+
+fn compute_fov(ox: i32, oy: i32, range: i32, map: &Map) -> Vec<(i32, i32)> {
+    let mut visible = vec![(ox, oy)];
+    for octant in &FOV_OCTANTS {
+        cast_light(map, ox, oy, 1, 1.0, 0.0, range, octant[0], octant[1], octant[2], octant[3], &mut visible);
+    }
+    visible
+}
+
+/// Carves a random set of non-overlapping rooms and connects each one to the
+/// previous room with an L-shaped tunnel. Returns the finished map along with
+/// the rooms that were placed, so callers can spawn things in open space.
+fn make_map() -> (Map, Vec<Rect>) {
     let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-    let room1 = Rect::new(20, 15, 10, 15);
-    let room2 = Rect::new(50, 15, 10, 15);
-    add_room(room1, &mut map);
-    add_room(room2, &mut map);
-    add_h_tunnel(25, 55, 23, &mut map);
-    map
+    let mut rooms: Vec<Rect> = Vec::new();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(MAP_SEED);
+
+    for _ in 0..MAX_ROOMS {
+        let w = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let h = rng.gen_range(ROOM_MIN_SIZE..=ROOM_MAX_SIZE);
+        let x = rng.gen_range(1..MAP_WIDTH - w - 1);
+        let y = rng.gen_range(1..MAP_HEIGHT - h - 1);
+        let new_room = Rect::new(x, y, w, h);
+
+        if rooms.iter().any(|other| new_room.intersects(other)) {
+            continue;
+        }
+
+        add_room(new_room, &mut map);
+        let (new_x, new_y) = new_room.center();
+
+        if let Some(prev_room) = rooms.last() {
+            let (prev_x, prev_y) = prev_room.center();
+            if rng.gen_bool(0.5) {
+                add_h_tunnel(prev_x, new_x, prev_y, &mut map);
+                add_v_tunnel(prev_y, new_y, new_x, &mut map);
+            } else {
+                add_v_tunnel(prev_y, new_y, prev_x, &mut map);
+                add_h_tunnel(prev_x, new_x, new_y, &mut map);
+            }
+        }
+
+        rooms.push(new_room);
+    }
+
+    (map, rooms)
 }
 
 fn render_all(tcod: &mut Tcod, game: &Game) {
-    // draw all objects
+    let visible = &game.objects[0].viewshed.as_ref().unwrap().visible_tiles;
+    let camera = &tcod.camera;
+
+    // draw the slice of the map inside the camera's window
+    (camera.top_y..camera.bottom_y).for_each(|y| (camera.left_x..camera.right_x).for_each(|x| {
+        let is_visible = visible.contains(&(x, y));
+        let is_wall = game.map[x as usize][y as usize].block_sight;
+        let color = match (is_visible, is_wall) {
+            (true, true) => COLOR_LIGHT_WALL,
+            (true, false) => COLOR_LIGHT_GROUND,
+            (false, true) => COLOR_DARK_WALL,
+            (false, false) => COLOR_DARK_GROUND,
+        };
+        let (screen_x, screen_y) = (x - camera.left_x, y - camera.top_y);
+        if is_visible || game.explored[x as usize][y as usize] {
+            tcod.con.set_char_background(screen_x, screen_y, color, BackgroundFlag::Set);
+        } else {
+            tcod.con.set_char_background(screen_x, screen_y, BLACK, BackgroundFlag::Set);
+        }
+    }));
+
+    // draw all objects that are currently visible and inside the camera window
     for object in &game.objects {
-        object.draw(&mut tcod.con);
+        let in_camera = object.x >= camera.left_x && object.x < camera.right_x
+            && object.y >= camera.top_y && object.y < camera.bottom_y;
+        if in_camera && visible.contains(&(object.x, object.y)) {
+            let screen_x = object.x - camera.left_x;
+            let screen_y = object.y - camera.top_y;
+            tcod.con.set_default_foreground(object.color);
+            tcod.con.put_char(screen_x, screen_y, object.char, BackgroundFlag::None);
+        }
     }
 
-    // draw other stuff
-    (0..MAP_HEIGHT).for_each(|y| (0..MAP_WIDTH).for_each(|x| 
-        if game.map[x as usize][y as usize].block_sight {
-            tcod.con.set_char_background(x, y, COLOR_DARK_WALL, BackgroundFlag::Set);
+    // add offscreen to screen
+    blit(&tcod.con, (0, 0), (DISPLAY_WIDTH, DISPLAY_HEIGHT), &mut tcod.root, (0, 0), 1.0, 1.0);
+
+    render_panel(tcod, game);
+}
+
+/// Word-wraps `text` to `width` columns, breaking only at spaces. This is a
+/// `print_rect`/`get_height_rect`-style helper: the caller gets the wrapped
+/// lines back and decides how to print/color/trim them.
+fn wrap_text(text: &str, width: i32) -> Vec<String> {
+    let width = width as usize;
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        if current.is_empty() {
+            current.push_str(word);
+        } else if current.len() + 1 + word.len() <= width {
+            current.push(' ');
+            current.push_str(word);
         } else {
-            tcod.con.set_char_background(x, y, COLOR_DARK_GROUND, BackgroundFlag::Set);
+            lines.push(current);
+            current = word.to_string();
         }
-    ));
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
 
-    // add offscreen to screen
-    blit(&tcod.con, (0, 0), (MAP_WIDTH, MAP_HEIGHT), &mut tcod.root, (0, 0), 1.0, 1.0);
+    lines
+}
+
+fn render_panel(tcod: &mut Tcod, game: &Game) {
+    tcod.panel.set_default_background(BLACK);
+    tcod.panel.clear();
+
+    let mut lines: Vec<(String, Color)> = Vec::new();
+    for (text, color) in &game.log {
+        for line in wrap_text(text, MSG_WIDTH) {
+            lines.push((line, *color));
+        }
+    }
+
+    let start = lines.len().saturating_sub(MSG_HEIGHT as usize);
+    for (i, (line, color)) in lines[start..].iter().enumerate() {
+        tcod.panel.set_default_foreground(*color);
+        tcod.panel.print_ex(MSG_X, 1 + i as i32, BackgroundFlag::None, TextAlignment::Left, line);
+    }
+
+    blit(&tcod.panel, (0, 0), (SCREEN_WIDTH, PANEL_HEIGHT), &mut tcod.root, (0, PANEL_Y), 1.0, 1.0);
 }
 
 fn game_loop(mut tcod: Tcod, mut game: Game) {
     while !tcod.root.window_closed() {
-        
+        match game.state {
+            RunState::PreRun => {
+                recompute_player_fov(&mut game);
+                tcod.camera.on_player_move(game.objects[0].x, game.objects[0].y);
+                game.state = RunState::AwaitingInput;
+            }
+            RunState::AwaitingInput => (),
+            RunState::PlayerTurn => {
+                recompute_player_fov(&mut game);
+                tcod.camera.on_player_move(game.objects[0].x, game.objects[0].y);
+                game.state = RunState::MonsterTurn;
+            }
+            RunState::MonsterTurn => {
+                run_monsters(&mut game);
+                game.state = RunState::AwaitingInput;
+            }
+        }
+
         // render stuff
         tcod.con.clear();
 
         render_all(&mut tcod, &game);
-        
+
         tcod.root.flush();
 
-        if handle_keys(&mut tcod, &mut game.objects[0], &game.map) {
-            break;
+        if let RunState::AwaitingInput = game.state {
+            match handle_keys(&mut tcod, &mut game.objects[0], &game.map) {
+                PlayerAction::Exit => break,
+                PlayerAction::TookTurn => game.state = RunState::PlayerTurn,
+                PlayerAction::DidntTakeTurn => (),
+            }
+        }
+    }
+}
+
+/// Steps every AI-controlled object one tile toward the player, but only if
+/// it's currently within the player's viewshed; otherwise it idles.
+fn run_monsters(game: &mut Game) {
+    let (player_x, player_y) = (game.objects[0].x, game.objects[0].y);
+    let visible = game.objects[0].viewshed.as_ref().unwrap().visible_tiles.clone();
+    let map = &game.map;
+
+    for object in game.objects.iter_mut().skip(1) {
+        if object.ai.is_none() || !visible.contains(&(object.x, object.y)) {
+            continue;
         }
-        //tcod.root.wait_for_keypress(true);
+        let dx = (player_x - object.x).signum();
+        let dy = (player_y - object.y).signum();
+        object.move_by(dx, dy, map);
+    }
+}
+
+/// Recomputes the player's viewshed if it's dirty and marks the newly
+/// visible tiles as explored, so they stay dimly lit once out of sight.
+fn recompute_player_fov(game: &mut Game) {
+    let player = &mut game.objects[0];
+    let viewshed = player.viewshed.as_mut().unwrap();
+    if !viewshed.dirty {
+        return;
+    }
+    viewshed.visible_tiles = compute_fov(player.x, player.y, viewshed.range, &game.map);
+    viewshed.dirty = false;
+    for &(x, y) in &player.viewshed.as_ref().unwrap().visible_tiles {
+        game.explored[x as usize][y as usize] = true;
     }
 }
 
-fn handle_keys(tcod: &mut Tcod, player: &mut Object, map: &Map) -> bool {
+/// Whether a keypress advanced the game to the next turn.
+enum PlayerAction {
+    TookTurn,
+    DidntTakeTurn,
+    Exit,
+}
+
+fn handle_keys(tcod: &mut Tcod, player: &mut Object, map: &Map) -> PlayerAction {
+    use PlayerAction::*;
+
     let Key {code, alt, ..} = tcod.root.wait_for_keypress(true);
     match (code, alt) {
-        (Up, _)     => player.move_by(0, -1, &map),
-        (Down, _)   => player.move_by(0, 1, &map),
-        (Left, _)   => player.move_by(-1, 0, &map),
-        (Right, _)  => player.move_by(1, 0, &map),
-        (Escape, _) => return true,
-        _ => (),
+        (Up, _)     => { player.move_by(0, -1, &map); TookTurn }
+        (Down, _)   => { player.move_by(0, 1, &map); TookTurn }
+        (Left, _)   => { player.move_by(-1, 0, &map); TookTurn }
+        (Right, _)  => { player.move_by(1, 0, &map); TookTurn }
+        (Escape, _) => Exit,
+        _ => DidntTakeTurn,
     }
-    false
 }
 
 fn main() {
@@ -165,13 +503,21 @@ fn main() {
         .title("Rust Roguelike")
         .init();
     tcod::system::set_fps(LIMIT_FPS);
-    let con = Offscreen::new(MAP_WIDTH, MAP_HEIGHT);
-    let tcod = Tcod { root, con };
-    let player = Object::new(25, 23, '@', WHITE);
-    let npc = Object::new(25, 25, '@', YELLOW);
+    let con = Offscreen::new(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+    let panel = Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT);
+    let (map, rooms) = make_map();
+    let (player_x, player_y) = rooms[0].center();
+    let camera = Camera::new(player_x, player_y);
+    let tcod = Tcod { root, con, panel, camera };
+    let mut player = Object::new(player_x, player_y, '@', WHITE);
+    player.viewshed = Some(Viewshed::new(10));
+    let mut npc = Object::new(player_x, player_y + 2, '@', YELLOW);
+    npc.ai = Some(Ai::Basic);
     let objects = vec![player, npc];
 
-    let game = Game {map: make_map(), objects};
+    let explored = vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    let mut game = Game {map, objects, explored, state: RunState::PreRun, log: Vec::new()};
+    game.log("Welcome to the dungeon. Find your way.", WHITE);
 
     game_loop(tcod, game);
 }